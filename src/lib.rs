@@ -1,7 +1,14 @@
 extern crate redis;
 extern crate chrono;
+extern crate async_trait;
+extern crate r2d2;
+extern crate tokio;
 
+use std::collections::HashMap;
 use std::default::Default;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use chrono::{DateTime, Utc};
 use redis::{
@@ -9,12 +16,39 @@ use redis::{
     Script as RedisScript,
     Commands,
 };
+use redis::aio::MultiplexedConnection;
+use redis::AsyncCommands;
+use redis::cluster::ClusterClient;
+use async_trait::async_trait;
+use r2d2::Pool as RedisPool;
 
 const LUA_SCRIPT: &str = include_str!("limiter.lua");
+const GCRA_LUA_SCRIPT: &str = include_str!("gcra.lua");
 const KEY_PREFIX: &str = "limiter";
 const REDIS_HOST: &str = "localhost";
 const REDIS_PORT: u16 = 6379;
 const REDIS_DB: u16 = 0;
+const POOL_SIZE: u32 = 16;
+/// Extra seconds of TTL added on top of `interval * 2` for token-bucket keys
+/// so a key outlives a full refill cycle even if nobody touches it again.
+const TOKEN_BUCKET_EXPIRE_MARGIN_SECS: u32 = 15;
+/// GCRA already derives its TTL from the TAT itself (see gcra.lua), so it
+/// only needs a small cushion against clock/scheduling jitter, not a second
+/// whole `interval`.
+const GCRA_EXPIRE_MARGIN_SECS: u32 = 1;
+
+/// Which Lua script `RedisLimiter` runs: a refilling token bucket (tracks
+/// `tokens` + `last_fill_at`), or GCRA (tracks a single theoretical arrival
+/// time per key) for smooth pacing and an exact retry-after.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    TokenBucket,
+    Gcra,
+}
+
+impl Default for Algorithm {
+    fn default() -> Self { Algorithm::TokenBucket }
+}
 
 fn timestamp_ms(t: DateTime<Utc>) -> i64 {
     t.timestamp() * 1000 + i64::from(t.timestamp_subsec_millis())
@@ -24,6 +58,16 @@ fn now_ms() -> i64 {
     timestamp_ms(Utc::now())
 }
 
+fn single_redis_key(prefix: &str, key: &str, interval: u32) -> String {
+    format!("{}:{}:{}", prefix, key, interval)
+}
+
+/// Hash-tags the variable portion of the key so every arg for one logical
+/// bucket lands in the same cluster slot regardless of prefix/interval.
+fn cluster_redis_key(prefix: &str, key: &str, interval: u32) -> String {
+    format!("{}:{{{}}}:{}", prefix, key, interval)
+}
+
 pub trait Limiter {
     fn get_token_count<'a>(&self, key: &'a str, interval: u32) -> Option<u32>;
     fn consume<'a>(&self, args: Vec<(&'a str, u32, u32, u32)>)
@@ -32,6 +76,34 @@ pub trait Limiter {
                        -> Result<(), RedisConsumeError> {
         self.consume(vec![(key, interval, capacity, n)])
     }
+
+    /// Like `consume_one`, but on `Denied` sleeps until `n` tokens are
+    /// expected to be available and retries, instead of failing immediately.
+    /// Gives up and returns the last `Denied` error as soon as the expected
+    /// wait would run past `max_wait`, so this never blocks longer than that.
+    /// Any other error is returned at once.
+    fn consume_one_blocking<'a>(
+        &self,
+        key: &'a str, interval: u32, capacity: u32, n: u32,
+        max_wait: Duration,
+    ) -> Result<(), RedisConsumeError> {
+        let deadline = Instant::now() + max_wait;
+        loop {
+            let err = match self.consume_one(key, interval, capacity, n) {
+                Ok(()) => return Ok(()),
+                Err(err) => err,
+            };
+            let wait = match err.wait_ms(n) {
+                Some(ms) => Duration::from_millis(ms as u64),
+                None => return Err(err),
+            };
+            let now = Instant::now();
+            if now >= deadline || wait > deadline - now {
+                return Err(err);
+            }
+            thread::sleep(wait);
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -42,43 +114,103 @@ pub enum RedisConsumeError {
         capacity: u32,
         current_tokens: u32,
         last_fill_at: i64,
+        /// Set when the GCRA algorithm denies a request: exactly how long to
+        /// wait before `n` tokens would be available, for a `Retry-After` header.
+        retry_after_ms: Option<u32>,
     },
     BadArg(String),
-    Redis(redis::RedisError)
+    Redis(redis::RedisError),
+    Pool(r2d2::Error),
+}
+
+impl RedisConsumeError {
+    /// For a `Denied` error, how long to wait before `n` tokens should be
+    /// available: `retry_after_ms` verbatim for GCRA, or a refill estimate
+    /// from `current_tokens`/`last_fill_at`/`capacity`/`interval` for the
+    /// token bucket. `None` for anything that isn't a capacity denial.
+    pub fn wait_ms(&self, n: u32) -> Option<i64> {
+        match *self {
+            RedisConsumeError::Denied { retry_after_ms: Some(ms), .. } => Some(i64::from(ms)),
+            RedisConsumeError::Denied {
+                interval, capacity, current_tokens, last_fill_at, retry_after_ms: None, ..
+            } => {
+                let per_token_ms = i64::from(interval) * 1000 / i64::from(capacity.max(1));
+                let needed = i64::from(n.saturating_sub(current_tokens));
+                let elapsed = now_ms() - last_fill_at;
+                Some((needed * per_token_ms - elapsed).max(0))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Where `RedisLimiter` sends its `EVALSHA` invocations: a pool of connections
+/// to a single node, or a pool of cluster connections (`redis`'s `r2d2`
+/// support implements `ManageConnection` for `ClusterClient` the same way it
+/// does for `Client`), so cluster mode gets the same concurrent-caller
+/// throughput as the single-node path instead of serializing every call
+/// behind one shared connection.
+enum RedisBackend {
+    Single(RedisPool<RedisClient>),
+    Cluster(RedisPool<ClusterClient>),
 }
 
 pub struct RedisLimiter {
-    redis_cli: RedisClient,
+    backend: RedisBackend,
     key_prefix: String,
     script: RedisScript,
+    algorithm: Algorithm,
 }
 
 #[derive(Default)]
 pub struct RedisLimiterBuilder<'a> {
     redis_cli: Option<RedisClient>,
+    pool: Option<RedisPool<RedisClient>>,
+    pool_size: Option<u32>,
+    cluster_nodes: Option<Vec<&'a str>>,
     host: Option<&'a str>,
     port: Option<u16>,
     db: Option<u16>,
     key_prefix: Option<&'a str>,
     script_str: Option<&'a str>,
+    algorithm: Option<Algorithm>,
 }
 
 impl<'a> RedisLimiterBuilder<'a> {
     pub fn new() -> Self {
         RedisLimiterBuilder{
             redis_cli: None,
+            pool: None,
+            pool_size: None,
+            cluster_nodes: None,
             host: None,
             port: None,
             db: None,
             key_prefix: None,
             script_str: None,
+            algorithm: None,
         }
     }
     pub fn build(self) -> RedisLimiter {
-        let script_str = self.script_str.unwrap_or(LUA_SCRIPT);
+        let algorithm = self.algorithm.unwrap_or_default();
+        let script_str = self.script_str.unwrap_or(match algorithm {
+            Algorithm::TokenBucket => LUA_SCRIPT,
+            Algorithm::Gcra => GCRA_LUA_SCRIPT,
+        });
         let key_prefix = self.key_prefix.unwrap_or(KEY_PREFIX);
-        if let Some(redis_cli) = self.redis_cli {
-            RedisLimiter::new(redis_cli, key_prefix, script_str)
+        if let Some(nodes) = self.cluster_nodes {
+            let client = ClusterClient::new(nodes).unwrap();
+            let pool = RedisPool::builder()
+                .max_size(self.pool_size.unwrap_or(POOL_SIZE))
+                .build(client)
+                .unwrap();
+            return RedisLimiter::new(RedisBackend::Cluster(pool), key_prefix, script_str, algorithm);
+        }
+        if let Some(pool) = self.pool {
+            return RedisLimiter::new(RedisBackend::Single(pool), key_prefix, script_str, algorithm);
+        }
+        let redis_cli = if let Some(redis_cli) = self.redis_cli {
+            redis_cli
         } else {
             let url = format!(
                 "redis://{}:{}/{}",
@@ -86,15 +218,34 @@ impl<'a> RedisLimiterBuilder<'a> {
                 self.port.unwrap_or(REDIS_PORT),
                 self.db.unwrap_or(REDIS_DB)
             );
-            let client = RedisClient::open(url.as_str()).unwrap();
-            RedisLimiter::new(client, key_prefix, script_str)
-        }
+            RedisClient::open(url.as_str()).unwrap()
+        };
+        let pool = RedisPool::builder()
+            .max_size(self.pool_size.unwrap_or(POOL_SIZE))
+            .build(redis_cli)
+            .unwrap();
+        RedisLimiter::new(RedisBackend::Single(pool), key_prefix, script_str, algorithm)
     }
 
     pub fn redis_cli(&mut self, client: RedisClient) -> &mut Self {
         self.redis_cli = Some(client);
         self
     }
+    pub fn pool(&mut self, pool: RedisPool<RedisClient>) -> &mut Self {
+        self.pool = Some(pool);
+        self
+    }
+    pub fn pool_size(&mut self, value: u32) -> &mut Self {
+        self.pool_size = Some(value);
+        self
+    }
+    /// Switch to cluster mode: `nodes` are `redis://host:port` URLs of any
+    /// subset of the cluster's nodes. Keys are hash-tagged so a batched
+    /// `consume` call can still span several logical buckets.
+    pub fn cluster_nodes(&mut self, nodes: Vec<&'a str>) -> &mut Self {
+        self.cluster_nodes = Some(nodes);
+        self
+    }
     pub fn host(&mut self, value: &'a str) -> &mut Self {
         self.host = Some(value);
         self
@@ -115,21 +266,107 @@ impl<'a> RedisLimiterBuilder<'a> {
         self.script_str = Some(value);
         self
     }
+    /// Select the rate-limiting algorithm. Defaults to `Algorithm::TokenBucket`.
+    /// If `script_str` is also set, that script runs instead of the algorithm's
+    /// bundled one, but expiry, reply parsing and argument checks still follow
+    /// `algorithm`.
+    pub fn algorithm(&mut self, value: Algorithm) -> &mut Self {
+        self.algorithm = Some(value);
+        self
+    }
 }
 
 impl RedisLimiter {
-    pub fn new<'a>(
-        redis_cli: RedisClient,
+    fn new<'a>(
+        backend: RedisBackend,
         key_prefix: &'a str,
         script_str: &'a str,
+        algorithm: Algorithm,
     ) -> Self {
         let key_prefix = key_prefix.to_owned();
         let script = RedisScript::new(script_str);
-        RedisLimiter{ redis_cli, key_prefix, script }
+        RedisLimiter{ backend, key_prefix, script, algorithm }
+    }
+
+    pub fn is_cluster(&self) -> bool {
+        match self.backend {
+            RedisBackend::Cluster(_) => true,
+            RedisBackend::Single(_) => false,
+        }
     }
 
+    /// In cluster mode the variable portion of the key is wrapped in hash-tag
+    /// braces so every arg for one logical bucket lands in the same slot.
     pub fn get_redis_key<'a>(&self, key: &'a str, interval: u32) -> String {
-        format!("{}:{}:{}", self.key_prefix, key, interval)
+        match self.backend {
+            RedisBackend::Cluster(_) => cluster_redis_key(&self.key_prefix, key, interval),
+            RedisBackend::Single(_) => single_redis_key(&self.key_prefix, key, interval),
+        }
+    }
+
+    /// `limiter.lua`/`gcra.lua` only look at `KEYS[1]`/`ARGV[1..5]`, so each
+    /// `(key, interval, capacity, n)` tuple needs its own `EVALSHA` — packing
+    /// several into one invocation's `KEYS`/`ARGV` would only ever rate-limit
+    /// the first and silently ignore the rest. Stops at the first denial or
+    /// error, leaving any remaining tuples unchecked (and, for tuples already
+    /// applied earlier in the loop, already consumed).
+    fn invoke<'a, C: redis::ConnectionLike>(
+        &self,
+        conn: &mut C,
+        args: &[(&'a str, u32, u32, u32)],
+    ) -> Result<(), RedisConsumeError> {
+        let now_ms = now_ms();
+        for &(key, interval, capacity, n) in args {
+            let redis_key = self.get_redis_key(key, interval);
+            let expire = match self.algorithm {
+                Algorithm::TokenBucket => interval * 2 + TOKEN_BUCKET_EXPIRE_MARGIN_SECS,
+                Algorithm::Gcra => GCRA_EXPIRE_MARGIN_SECS,
+            };
+            let interval_ms = interval * 1000;
+            let result = self.script.prepare_invoke()
+                .key(redis_key)
+                .arg(interval_ms)
+                .arg(capacity)
+                .arg(n)
+                .arg(now_ms)
+                .arg(expire)
+                .invoke(conn);
+            match result {
+                Ok((_, 0, 0, 0, 0)) => {}
+                Ok((redis_key, interval_ms, capacity, third, fourth)) => {
+                    let interval = interval_ms / 1000;
+                    let (current_tokens, last_fill_at, retry_after_ms) = match self.algorithm {
+                        Algorithm::TokenBucket => (third, fourth, None),
+                        Algorithm::Gcra => (0, 0, Some(third)),
+                    };
+                    return Err(RedisConsumeError::Denied{
+                        redis_key, interval, capacity,
+                        current_tokens, last_fill_at, retry_after_ms,
+                    });
+                }
+                Err(e) => return Err(RedisConsumeError::Redis(e)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Group args by key: with hash-tagged keys, equal keys always share a
+    /// slot, so grouping this way never merges two different slots into one
+    /// invocation. Each group is then passed to `invoke`, which still sends
+    /// one EVALSHA per tuple.
+    fn group_by_slot<'a>(
+        args: Vec<(&'a str, u32, u32, u32)>,
+    ) -> Vec<Vec<(&'a str, u32, u32, u32)>> {
+        let mut order: Vec<&'a str> = Vec::new();
+        let mut groups: HashMap<&'a str, Vec<(&'a str, u32, u32, u32)>> = HashMap::new();
+        for item in args {
+            let key = item.0;
+            groups.entry(key).or_insert_with(|| {
+                order.push(key);
+                Vec::new()
+            }).push(item);
+        }
+        order.into_iter().map(|key| groups.remove(key).unwrap()).collect()
     }
 }
 
@@ -139,59 +376,300 @@ impl Default for RedisLimiter {
 
 impl Limiter for RedisLimiter {
     fn get_token_count<'a>(&self, key: &'a str, interval: u32) -> Option<u32> {
-        self.redis_cli
-            .get_connection()
-            .unwrap()
-            .hget(self.get_redis_key(key, interval), "tokens")
-            .ok()
+        // GCRA stores a single arrival time per key, not a token count.
+        if self.algorithm == Algorithm::Gcra {
+            return None;
+        }
+        let redis_key = self.get_redis_key(key, interval);
+        match self.backend {
+            RedisBackend::Single(ref pool) => {
+                pool.get().unwrap().hget(redis_key, "tokens").ok()
+            }
+            RedisBackend::Cluster(ref pool) => {
+                pool.get().unwrap().hget(redis_key, "tokens").ok()
+            }
+        }
     }
 
     fn consume<'a>(&self, args: Vec<(&'a str, u32, u32, u32)>)
                    -> Result<(), RedisConsumeError> {
-        let now_ms = now_ms();
-        let mut invocation = self.script.prepare_invoke();
-        for (ref key, interval, capacity, n) in args {
+        for &(key, interval, capacity, n) in &args {
+            if key.len() < 1 || n < 1 || interval < 1 || capacity < 1 {
+                return Err(RedisConsumeError::BadArg(format!(
+                    "[BadArg]: key={}, interval={}, capacity={}, n={}",
+                    key, interval, capacity, n
+                )));
+            }
+            if self.algorithm == Algorithm::Gcra && n > capacity {
+                return Err(RedisConsumeError::BadArg(format!(
+                    "[BadArg]: n={} exceeds capacity={} (burst tolerance)",
+                    n, capacity
+                )));
+            }
+        }
+        match self.backend {
+            RedisBackend::Single(ref pool) => {
+                let mut conn = pool.get().map_err(RedisConsumeError::Pool)?;
+                self.invoke(&mut *conn, &args)
+            }
+            RedisBackend::Cluster(ref pool) => {
+                let mut conn = pool.get().map_err(RedisConsumeError::Pool)?;
+                for group in RedisLimiter::group_by_slot(args) {
+                    self.invoke(&mut *conn, &group)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+struct CacheEntry {
+    tokens: u32,
+    expires_at: Instant,
+}
+
+/// Wraps any `Limiter` with a per-key local token estimate, modeled on
+/// limitador's counters cache: most `consume` calls are decided in-process
+/// and only hit the wrapped limiter when the local estimate runs out or goes
+/// stale. The local TTL is `interval - ttl_margin` (at least one second), so
+/// an entry expires before the real bucket could have fully refilled. Local
+/// decrements are not written back to the wrapped limiter, so concurrent
+/// callers can be over-admitted; and an entry whose estimate has run out
+/// denies until it expires, even if the real bucket has refilled some tokens.
+pub struct CachedLimiter<L: Limiter> {
+    inner: L,
+    ttl_margin: u32,
+    cache: Mutex<HashMap<String, CacheEntry>>,
+}
+
+impl<L: Limiter> CachedLimiter<L> {
+    pub fn new(inner: L, ttl_margin: u32) -> Self {
+        CachedLimiter {
+            inner,
+            ttl_margin,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn cache_key<'a>(key: &'a str, interval: u32) -> String {
+        format!("{}:{}", key, interval)
+    }
+
+    fn consume_cached<'a>(&self, key: &'a str, interval: u32, capacity: u32, n: u32)
+                         -> Result<(), RedisConsumeError> {
+        let cache_key = Self::cache_key(key, interval);
+        let now = Instant::now();
+        {
+            let mut cache = self.cache.lock().unwrap();
+            match cache.get_mut(&cache_key) {
+                Some(entry) if now < entry.expires_at && entry.tokens >= n => {
+                    entry.tokens -= n;
+                    return Ok(());
+                }
+                Some(entry) if now < entry.expires_at => {
+                    return Err(RedisConsumeError::Denied {
+                        redis_key: cache_key,
+                        interval,
+                        capacity,
+                        current_tokens: entry.tokens,
+                        last_fill_at: now_ms(),
+                        retry_after_ms: None,
+                    });
+                }
+                _ => { cache.remove(&cache_key); }
+            }
+        }
+
+        let result = self.inner.consume_one(key, interval, capacity, n);
+        // Seed from the real remaining count, not a guess: on success we don't
+        // know how many tokens the bucket actually had before this call (the
+        // Lua script reports all-zeros on allow), so re-read it instead of
+        // assuming the bucket was full.
+        let seeded_tokens = match &result {
+            Ok(()) => self.inner.get_token_count(key, interval),
+            Err(RedisConsumeError::Denied { current_tokens, .. }) => Some(*current_tokens),
+            _ => None,
+        };
+        if let Some(tokens) = seeded_tokens {
+            let ttl = Duration::from_secs(interval.saturating_sub(self.ttl_margin).max(1) as u64);
+            self.cache.lock().unwrap().insert(cache_key, CacheEntry {
+                tokens,
+                expires_at: now + ttl,
+            });
+        }
+        result
+    }
+}
+
+impl<L: Limiter> Limiter for CachedLimiter<L> {
+    fn get_token_count<'a>(&self, key: &'a str, interval: u32) -> Option<u32> {
+        self.inner.get_token_count(key, interval)
+    }
+
+    fn consume<'a>(&self, args: Vec<(&'a str, u32, u32, u32)>)
+                   -> Result<(), RedisConsumeError> {
+        // Only the common single-bucket call is worth caching; batched
+        // multi-key calls always go straight through to the wrapped limiter.
+        if let [(key, interval, capacity, n)] = args[..] {
+            return self.consume_cached(key, interval, capacity, n);
+        }
+        self.inner.consume(args)
+    }
+}
+
+/// Async counterpart of [`Limiter`]; `AsyncRedisLimiter` implements it over a
+/// single shared `MultiplexedConnection` instead of a fresh socket per call.
+/// That connection is opened once and is not re-established if it drops.
+#[async_trait]
+pub trait AsyncLimiter {
+    async fn get_token_count<'a>(&self, key: &'a str, interval: u32) -> Option<u32>;
+    async fn consume<'a>(&self, args: Vec<(&'a str, u32, u32, u32)>)
+                         -> Result<(), RedisConsumeError>;
+    async fn consume_one<'a>(&self, key: &'a str, interval: u32, capacity: u32, n: u32)
+                             -> Result<(), RedisConsumeError> {
+        self.consume(vec![(key, interval, capacity, n)]).await
+    }
+
+    /// Async counterpart of `Limiter::consume_one_blocking`: on `Denied`,
+    /// sleeps until `n` tokens are expected to be available and retries,
+    /// giving up as soon as the expected wait would run past `max_wait`.
+    async fn consume_one_wait<'a>(
+        &self,
+        key: &'a str, interval: u32, capacity: u32, n: u32,
+        max_wait: Duration,
+    ) -> Result<(), RedisConsumeError> {
+        let deadline = Instant::now() + max_wait;
+        loop {
+            let err = match self.consume_one(key, interval, capacity, n).await {
+                Ok(()) => return Ok(()),
+                Err(err) => err,
+            };
+            let wait = match err.wait_ms(n) {
+                Some(ms) => Duration::from_millis(ms as u64),
+                None => return Err(err),
+            };
+            let now = Instant::now();
+            if now >= deadline || wait > deadline - now {
+                return Err(err);
+            }
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// `AsyncLimiter` built on `redis::aio::MultiplexedConnection`, so every call is
+/// multiplexed over one connection instead of opening a new one each time.
+pub struct AsyncRedisLimiter {
+    conn: MultiplexedConnection,
+    key_prefix: String,
+    script: RedisScript,
+    algorithm: Algorithm,
+}
+
+impl AsyncRedisLimiter {
+    pub async fn new<'a>(
+        redis_cli: RedisClient,
+        key_prefix: &'a str,
+        script_str: &'a str,
+    ) -> redis::RedisResult<Self> {
+        AsyncRedisLimiter::with_algorithm(redis_cli, key_prefix, script_str, Algorithm::TokenBucket).await
+    }
+
+    /// Like `new`, but states which `Algorithm` `script_str` implements
+    /// instead of always assuming a token-bucket script, so expiry, reply
+    /// parsing and argument checks match it. Unlike
+    /// `RedisLimiterBuilder::algorithm`, this does not pick the script:
+    /// `script_str` is always the one that runs.
+    pub async fn with_algorithm<'a>(
+        redis_cli: RedisClient,
+        key_prefix: &'a str,
+        script_str: &'a str,
+        algorithm: Algorithm,
+    ) -> redis::RedisResult<Self> {
+        let conn = redis_cli.get_multiplexed_async_connection().await?;
+        Ok(AsyncRedisLimiter {
+            conn,
+            key_prefix: key_prefix.to_owned(),
+            script: RedisScript::new(script_str),
+            algorithm,
+        })
+    }
+
+    pub fn get_redis_key<'a>(&self, key: &'a str, interval: u32) -> String {
+        single_redis_key(&self.key_prefix, key, interval)
+    }
+}
+
+#[async_trait]
+impl AsyncLimiter for AsyncRedisLimiter {
+    async fn get_token_count<'a>(&self, key: &'a str, interval: u32) -> Option<u32> {
+        if self.algorithm == Algorithm::Gcra {
+            return None;
+        }
+        let mut conn = self.conn.clone();
+        conn.hget(self.get_redis_key(key, interval), "tokens")
+            .await
+            .ok()
+    }
+
+    // `limiter.lua`/`gcra.lua` only look at `KEYS[1]`/`ARGV[1..5]`, so each
+    // tuple needs its own `EVALSHA`, same as the sync `RedisLimiter::invoke`.
+    async fn consume<'a>(&self, args: Vec<(&'a str, u32, u32, u32)>)
+                         -> Result<(), RedisConsumeError> {
+        for &(key, interval, capacity, n) in &args {
             if key.len() < 1 || n < 1 || interval < 1 || capacity < 1 {
                 return Err(RedisConsumeError::BadArg(format!(
                     "[BadArg]: key={}, interval={}, capacity={}, n={}",
                     key, interval, capacity, n
                 )));
             }
+            if self.algorithm == Algorithm::Gcra && n > capacity {
+                return Err(RedisConsumeError::BadArg(format!(
+                    "[BadArg]: n={} exceeds capacity={} (burst tolerance)",
+                    n, capacity
+                )));
+            }
+        }
+        let mut conn = self.conn.clone();
+        for &(key, interval, capacity, n) in &args {
+            let now_ms = now_ms();
             let redis_key = self.get_redis_key(key, interval);
-            let expire = interval * 2 + 15;
+            let expire = match self.algorithm {
+                Algorithm::TokenBucket => interval * 2 + TOKEN_BUCKET_EXPIRE_MARGIN_SECS,
+                Algorithm::Gcra => GCRA_EXPIRE_MARGIN_SECS,
+            };
             let interval_ms = interval * 1000;
-            invocation
+            let result = self.script.prepare_invoke()
                 .key(redis_key)
                 .arg(interval_ms)
                 .arg(capacity)
                 .arg(n)
                 .arg(now_ms)
-                .arg(expire);
-        }
-        let conn = try!{
-            self.redis_cli
-                .get_connection()
-                .map_err(|e| RedisConsumeError::Redis(e))
-        };
-        match invocation.invoke(&conn) {
-            Ok((_, 0, 0, 0, 0)) => Ok(()),
-            Ok((redis_key, interval_ms, capacity,
-                current_tokens, last_fill_at)) => {
-                let interval = interval_ms / 1000;
-                Err(RedisConsumeError::Denied{
-                    redis_key, interval, capacity,
-                    current_tokens, last_fill_at
-                })
+                .arg(expire)
+                .invoke_async(&mut conn)
+                .await;
+            match result {
+                Ok((_, 0, 0, 0, 0)) => {}
+                Ok((redis_key, interval_ms, capacity, third, fourth)) => {
+                    let interval = interval_ms / 1000;
+                    let (current_tokens, last_fill_at, retry_after_ms) = match self.algorithm {
+                        Algorithm::TokenBucket => (third, fourth, None),
+                        Algorithm::Gcra => (0, 0, Some(third)),
+                    };
+                    return Err(RedisConsumeError::Denied{
+                        redis_key, interval, capacity,
+                        current_tokens, last_fill_at, retry_after_ms,
+                    });
+                }
+                Err(e) => return Err(RedisConsumeError::Redis(e)),
             }
-            Err(e) => Err(RedisConsumeError::Redis(e))
         }
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::time::Duration;
-    use std::thread;
     use super::*;
 
     fn redis_client() -> RedisClient {
@@ -302,7 +780,7 @@ mod tests {
                     match err {
                         RedisConsumeError::Denied {
                             redis_key, interval, capacity,
-                            current_tokens, last_fill_at: _
+                            current_tokens, last_fill_at: _, retry_after_ms: _
                         } => {
                             assert_eq!(redis_key, limiter.get_redis_key(key_1.as_str(), interval_1));
                             assert_eq!(interval, interval_1);
@@ -321,4 +799,178 @@ mod tests {
 
         del_keys(&limiter, vec![(key_1.as_str(), interval_1), (key_2.as_str(), interval_2)]);
     }
+
+    /// In-memory `Limiter` used to unit-test `CachedLimiter`'s caching logic
+    /// without a live Redis.
+    struct FakeLimiter {
+        tokens: Mutex<HashMap<String, u32>>,
+    }
+
+    impl FakeLimiter {
+        fn new() -> Self {
+            FakeLimiter { tokens: Mutex::new(HashMap::new()) }
+        }
+    }
+
+    impl Limiter for FakeLimiter {
+        fn get_token_count<'a>(&self, key: &'a str, interval: u32) -> Option<u32> {
+            self.tokens.lock().unwrap().get(&format!("{}:{}", key, interval)).copied()
+        }
+
+        fn consume<'a>(&self, args: Vec<(&'a str, u32, u32, u32)>) -> Result<(), RedisConsumeError> {
+            let (key, interval, capacity, n) = args[0];
+            let cache_key = format!("{}:{}", key, interval);
+            let mut tokens = self.tokens.lock().unwrap();
+            let current = *tokens.entry(cache_key.clone()).or_insert(capacity);
+            if current >= n {
+                tokens.insert(cache_key, current - n);
+                Ok(())
+            } else {
+                Err(RedisConsumeError::Denied {
+                    redis_key: cache_key,
+                    interval, capacity,
+                    current_tokens: current,
+                    last_fill_at: 0,
+                    retry_after_ms: None,
+                })
+            }
+        }
+    }
+
+    #[test]
+    fn test_cached_limiter_decrements_locally_without_hitting_inner() {
+        let cached = CachedLimiter::new(FakeLimiter::new(), 1);
+        let key = "local";
+        let interval = 60;
+        let capacity = 3;
+
+        assert!(cached.consume_one(key, interval, capacity, 1).is_ok());
+        assert!(cached.consume_one(key, interval, capacity, 1).is_ok());
+        assert!(cached.consume_one(key, interval, capacity, 1).is_ok());
+        // Fourth call is denied by the exhausted cache entry alone; the fake
+        // was only hit by the first call and still holds 2 tokens.
+        assert!(cached.consume_one(key, interval, capacity, 1).is_err());
+    }
+
+    #[test]
+    fn test_cached_limiter_seeds_from_real_remaining_count_not_capacity_minus_n() {
+        let fake = FakeLimiter::new();
+        let key = "seed";
+        let interval = 60;
+        let capacity = 10;
+        // Drain the real bucket down to 1 token, bypassing the cache.
+        for _ in 0..9 {
+            assert!(fake.consume_one(key, interval, capacity, 1).is_ok());
+        }
+
+        let cached = CachedLimiter::new(fake, 1);
+        // Cache is empty, so this goes through to the real limiter, succeeds,
+        // and leaves the real bucket at 0.
+        assert!(cached.consume_one(key, interval, capacity, 1).is_ok());
+        // The cache must have been seeded with the real remaining count (0),
+        // not `capacity - n` (9), so the next call is denied locally.
+        assert!(cached.consume_one(key, interval, capacity, 1).is_err());
+    }
+
+    #[test]
+    fn test_wait_ms_uses_retry_after_verbatim_for_gcra() {
+        let err = RedisConsumeError::Denied {
+            redis_key: "k".to_owned(),
+            interval: 60, capacity: 10,
+            current_tokens: 0, last_fill_at: 0,
+            retry_after_ms: Some(250),
+        };
+        assert_eq!(err.wait_ms(1), Some(250));
+    }
+
+    #[test]
+    fn test_wait_ms_estimates_refill_for_token_bucket() {
+        let err = RedisConsumeError::Denied {
+            redis_key: "k".to_owned(),
+            interval: 10, capacity: 10,
+            current_tokens: 0, last_fill_at: now_ms(),
+            retry_after_ms: None,
+        };
+        // capacity/interval == 1 token/sec, so 1 more token needed is ~1000ms away.
+        let wait = err.wait_ms(1).unwrap();
+        assert!(wait > 0 && wait <= 1000, "wait_ms was {}", wait);
+    }
+
+    #[test]
+    fn test_wait_ms_none_for_non_capacity_errors() {
+        assert_eq!(RedisConsumeError::BadArg("x".to_owned()).wait_ms(1), None);
+    }
+
+    /// Denies the first `succeed_after - 1` calls with a short `retry_after_ms`,
+    /// then allows; used to drive `consume_one_blocking`'s retry loop without
+    /// a real clock-driven refill.
+    struct FlakyLimiter {
+        attempts: Mutex<u32>,
+        succeed_after: u32,
+    }
+
+    impl Limiter for FlakyLimiter {
+        fn get_token_count<'a>(&self, _key: &'a str, _interval: u32) -> Option<u32> {
+            None
+        }
+
+        fn consume<'a>(&self, _args: Vec<(&'a str, u32, u32, u32)>) -> Result<(), RedisConsumeError> {
+            let mut attempts = self.attempts.lock().unwrap();
+            *attempts += 1;
+            if *attempts >= self.succeed_after {
+                Ok(())
+            } else {
+                Err(RedisConsumeError::Denied {
+                    redis_key: "flaky".to_owned(),
+                    interval: 60, capacity: 1,
+                    current_tokens: 0, last_fill_at: 0,
+                    retry_after_ms: Some(5),
+                })
+            }
+        }
+    }
+
+    #[test]
+    fn test_consume_one_blocking_succeeds_once_capacity_frees_up() {
+        let flaky = FlakyLimiter { attempts: Mutex::new(0), succeed_after: 3 };
+        let result = flaky.consume_one_blocking("block", 60, 1, 1, Duration::from_secs(1));
+        assert!(result.is_ok());
+        assert_eq!(*flaky.attempts.lock().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_consume_one_blocking_gives_up_after_max_wait() {
+        let flaky = FlakyLimiter { attempts: Mutex::new(0), succeed_after: u32::max_value() };
+        let result = flaky.consume_one_blocking("block_timeout", 60, 1, 1, Duration::from_millis(20));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_gcra_margin_does_not_reuse_token_bucket_expire() {
+        // GCRA derives its own TTL from the TAT, so it should only need a
+        // small jitter cushion, not the token-bucket's `interval * 2 + 15`.
+        assert!(GCRA_EXPIRE_MARGIN_SECS < TOKEN_BUCKET_EXPIRE_MARGIN_SECS);
+    }
+
+    #[test]
+    fn test_cluster_redis_key_is_hash_tagged() {
+        assert_eq!(single_redis_key("limiter", "foo", 10), "limiter:foo:10");
+        assert_eq!(cluster_redis_key("limiter", "foo", 10), "limiter:{foo}:10");
+    }
+
+    #[test]
+    fn test_group_by_slot_keeps_same_key_together_and_preserves_order() {
+        let args = vec![
+            ("a", 1, 2, 1),
+            ("b", 1, 2, 1),
+            ("a", 1, 2, 1),
+            ("c", 1, 2, 1),
+        ];
+        let groups = RedisLimiter::group_by_slot(args);
+        let keys: Vec<&str> = groups.iter().map(|g| g[0].0).collect();
+        assert_eq!(keys, vec!["a", "b", "c"]);
+        assert_eq!(groups[0].len(), 2);
+        assert_eq!(groups[1].len(), 1);
+        assert_eq!(groups[2].len(), 1);
+    }
 }